@@ -54,20 +54,20 @@ fn handle_calendar_properties(
 
 fn handle_timezones(tzs: &[IcalTimeZone], _cfg: &Cfg, res: &mut String) -> anyhow::Result<()> {
     for tz in tzs {
-        *res += "BEGIN:VTIMEZONE\n";
+        *res += "BEGIN:VTIMEZONE\r\n";
         for p in &tz.properties {
             *res += &build_property(&p.name, &p.params, &p.value);
         }
         for transition in &tz.transitions {
             // TODO: ical doesn't expose whether it's BEGIN:DAYLIGHT or BEGIN:STANDARD
             // It probably doesn't matter anyway? I don't think the spec asks for any differential treatment at least
-            *res += "BEGIN:STANDARD\n";
+            *res += "BEGIN:STANDARD\r\n";
             for p in &transition.properties {
                 *res += &build_property(&p.name, &p.params, &p.value);
             }
-            *res += "END:STANDARD\n";
+            *res += "END:STANDARD\r\n";
         }
-        *res += "END:VTIMEZONE\n";
+        *res += "END:VTIMEZONE\r\n";
     }
     Ok(())
 }
@@ -81,17 +81,17 @@ fn handle_events(evts: &[IcalEvent], cfg: &Cfg, res: &mut String) -> anyhow::Res
             }
         }
         // Otherwise, output event
-        *res += "BEGIN:VEVENT\n";
+        *res += "BEGIN:VEVENT\r\n";
         for p in &e.properties {
             *res += &build_property(&p.name, &p.params, &p.value);
         }
-        *res += "END:VEVENT\n";
+        *res += "END:VEVENT\r\n";
     }
     Ok(())
 }
 
 fn generate_ics(cal: IcalCalendar, cfg: &Cfg) -> anyhow::Result<String> {
-    let mut res = "BEGIN:VCALENDAR\n".to_owned();
+    let mut res = "BEGIN:VCALENDAR\r\n".to_owned();
 
     handle_calendar_properties(&cal.properties, cfg, &mut res)
         .context("Handling the calendar properties")?;
@@ -114,7 +114,7 @@ fn generate_ics(cal: IcalCalendar, cfg: &Cfg) -> anyhow::Result<String> {
         "Parsed calendar had free_busys, this is not implemented yet, please open an issue"
     );
 
-    res += "END:VCALENDAR\n";
+    res += "END:VCALENDAR\r\n";
 
     Ok(res)
 }
@@ -0,0 +1,294 @@
+//! In-memory cache of fetched remote calendars, keyed by URL.
+//!
+//! Caching avoids hammering the upstream server (and adding its round-trip latency to every
+//! incoming request) when a path is fetched repeatedly. An entry is considered fresh for a TTL
+//! that defaults to the configured default, overridden by the remote calendar's own
+//! `REFRESH-INTERVAL` property when it has one. Once an entry goes stale, a conditional
+//! `If-None-Match`/`If-Modified-Since` request lets a `304 Not Modified` refresh the timestamp
+//! without re-parsing the body.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use ical::parser::ical::component::IcalCalendar;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+struct Entry {
+    calendar: IcalCalendar,
+    fetched_at: DateTime<Utc>,
+    ttl: Duration,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// The process-wide remote-calendar cache, managed as Rocket state.
+///
+/// Holds a single [`reqwest::Client`] shared across every fetch, so repeated requests to the same
+/// (or different) upstream servers reuse pooled, keep-alive connections instead of paying a fresh
+/// TCP/TLS handshake each time.
+pub struct Cache {
+    entries: Mutex<HashMap<url::Url, Entry>>,
+    client: reqwest::Client,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Cache {
+    /// Fetch and parse `url`, reusing a cached copy if it's still within its TTL.
+    ///
+    /// `default_ttl` is the TTL stored for any fetch whose calendar doesn't carry a
+    /// `REFRESH-INTERVAL`; a cached entry's freshness is judged by the TTL stored with it.
+    pub async fn fetch(&self, url: &url::Url, default_ttl: Duration) -> anyhow::Result<IcalCalendar> {
+        if let Some(calendar) = self.fresh(url) {
+            return Ok(calendar);
+        }
+
+        let (etag, last_modified) = self.conditional_headers(url);
+        let mut req = self.client.get(url.as_str());
+        if let Some(etag) = &etag {
+            req = req.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+        let response = req
+            .send()
+            .await
+            .with_context(|| format!("Fetching remote URL {}", url))?;
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(calendar) = self.touch(url) {
+                return Ok(calendar);
+            }
+        }
+        anyhow::ensure!(
+            response.status().is_success(),
+            "Remote URL {} did not reply with a successful code: {:?}",
+            url,
+            response.status()
+        );
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let last_modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let text = response
+            .text()
+            .await
+            .with_context(|| format!("Recovering the text part of the remote URL {}", url))?;
+
+        let calendars = ical::IcalParser::new(text.as_bytes()).collect::<Vec<_>>();
+        anyhow::ensure!(calendars.len() == 1, "Remote URL {} had multiple calendars, this is not supported yet, please open an issue if you have a use case for it", url);
+        let calendar = calendars
+            .into_iter()
+            .next()
+            .unwrap() // see ensure! just above
+            .with_context(|| format!("Failed to parse the calendar for remote URL {}", url))?;
+
+        let ttl = refresh_interval(&calendar).unwrap_or(default_ttl);
+        let entry = Entry {
+            calendar: calendar.clone(),
+            fetched_at: Utc::now(),
+            ttl,
+            etag,
+            last_modified,
+        };
+        self.entries.lock().unwrap().insert(url.clone(), entry);
+        Ok(calendar)
+    }
+
+    fn fresh(&self, url: &url::Url) -> Option<IcalCalendar> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(url)?;
+        (Utc::now() - entry.fetched_at < entry.ttl).then(|| entry.calendar.clone())
+    }
+
+    fn conditional_headers(&self, url: &url::Url) -> (Option<String>, Option<String>) {
+        match self.entries.lock().unwrap().get(url) {
+            Some(entry) => (entry.etag.clone(), entry.last_modified.clone()),
+            None => (None, None),
+        }
+    }
+
+    /// Record that a `304 Not Modified` was just observed for `url`, returning the still-valid
+    /// cached calendar.
+    fn touch(&self, url: &url::Url) -> Option<IcalCalendar> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(url)?;
+        entry.fetched_at = Utc::now();
+        Some(entry.calendar.clone())
+    }
+}
+
+/// Parse the calendar's `REFRESH-INTERVAL` property, if present, as the TTL to use until the next
+/// fetch.
+fn refresh_interval(calendar: &IcalCalendar) -> Option<Duration> {
+    let value = calendar
+        .properties
+        .iter()
+        .find(|p| p.name == "REFRESH-INTERVAL")?
+        .value
+        .as_deref()?;
+    parse_ics_duration(value).ok()
+}
+
+/// Parse an RFC 5545 `DURATION` value (the part after `REFRESH-INTERVAL:`), e.g. `PT1H` or
+/// `P1DT2H`. The `nW` weeks form is also accepted when combined with other designators (e.g.
+/// `P1W2D`), although the spec forbids mixing them.
+fn parse_ics_duration(value: &str) -> anyhow::Result<Duration> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value
+        .strip_prefix('P')
+        .context("DURATION value must start with P")?;
+    let (date_part, time_part) = match value.split_once('T') {
+        Some((d, t)) => (d, Some(t)),
+        None => (value, None),
+    };
+
+    let mut duration = Duration::zero();
+    duration = duration + sum_designated_parts(date_part, |c, n| match c {
+        'W' => Ok(Duration::weeks(n)),
+        'D' => Ok(Duration::days(n)),
+        other => anyhow::bail!("Unsupported DURATION date designator {:?}", other),
+    })?;
+    if let Some(time_part) = time_part {
+        duration = duration + sum_designated_parts(time_part, |c, n| match c {
+            'H' => Ok(Duration::hours(n)),
+            'M' => Ok(Duration::minutes(n)),
+            'S' => Ok(Duration::seconds(n)),
+            other => anyhow::bail!("Unsupported DURATION time designator {:?}", other),
+        })?;
+    }
+    Ok(duration * sign)
+}
+
+fn sum_designated_parts(
+    part: &str,
+    to_duration: impl Fn(char, i64) -> anyhow::Result<Duration>,
+) -> anyhow::Result<Duration> {
+    let mut duration = Duration::zero();
+    let mut num = String::new();
+    for c in part.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+        } else {
+            let n: i64 = num.parse().context("Parsing DURATION number")?;
+            num.clear();
+            duration = duration + to_duration(c, n)?;
+        }
+    }
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar() -> IcalCalendar {
+        IcalCalendar::default()
+    }
+
+    #[test]
+    fn parse_ics_duration_hours() {
+        assert_eq!(parse_ics_duration("PT1H").unwrap(), Duration::hours(1));
+    }
+
+    #[test]
+    fn parse_ics_duration_mixed_date_and_time() {
+        assert_eq!(
+            parse_ics_duration("P1DT2H").unwrap(),
+            Duration::days(1) + Duration::hours(2)
+        );
+    }
+
+    #[test]
+    fn parse_ics_duration_weeks() {
+        assert_eq!(parse_ics_duration("P2W").unwrap(), Duration::weeks(2));
+    }
+
+    #[test]
+    fn parse_ics_duration_negative() {
+        assert_eq!(parse_ics_duration("-PT30M").unwrap(), Duration::minutes(-30));
+    }
+
+    #[test]
+    fn parse_ics_duration_explicit_plus() {
+        assert_eq!(parse_ics_duration("+PT1H").unwrap(), Duration::hours(1));
+    }
+
+    #[test]
+    fn parse_ics_duration_rejects_missing_p_prefix() {
+        assert!(parse_ics_duration("1H").is_err());
+    }
+
+    #[test]
+    fn fresh_entry_is_used_within_its_ttl() {
+        let cache = Cache::default();
+        let url = url::Url::parse("https://example.com/cal.ics").unwrap();
+        cache.entries.lock().unwrap().insert(
+            url.clone(),
+            Entry {
+                calendar: calendar(),
+                fetched_at: Utc::now(),
+                ttl: Duration::hours(1),
+                etag: None,
+                last_modified: None,
+            },
+        );
+        assert!(cache.fresh(&url).is_some());
+    }
+
+    #[test]
+    fn stale_entry_is_not_used() {
+        let cache = Cache::default();
+        let url = url::Url::parse("https://example.com/cal.ics").unwrap();
+        cache.entries.lock().unwrap().insert(
+            url.clone(),
+            Entry {
+                calendar: calendar(),
+                fetched_at: Utc::now() - Duration::hours(2),
+                ttl: Duration::hours(1),
+                etag: None,
+                last_modified: None,
+            },
+        );
+        assert!(cache.fresh(&url).is_none());
+    }
+
+    #[test]
+    fn touch_refreshes_a_stale_entry_into_a_fresh_one() {
+        let cache = Cache::default();
+        let url = url::Url::parse("https://example.com/cal.ics").unwrap();
+        cache.entries.lock().unwrap().insert(
+            url.clone(),
+            Entry {
+                calendar: calendar(),
+                fetched_at: Utc::now() - Duration::hours(2),
+                ttl: Duration::hours(1),
+                etag: None,
+                last_modified: None,
+            },
+        );
+        assert!(cache.fresh(&url).is_none());
+        assert!(cache.touch(&url).is_some());
+        assert!(cache.fresh(&url).is_some());
+    }
+}
@@ -0,0 +1,88 @@
+//! Minimal CalDAV `calendar-query` REPORT support (RFC 4791 §7.8): time-range filtering and a
+//! WebDAV `multistatus` response.
+//!
+//! Real CalDAV clients issue a `calendar-query` as the WebDAV `REPORT` HTTP method, but Rocket's
+//! `http::Method` only models the fixed IANA method set used by `get`/`post`/etc. and has no
+//! `REPORT` (or other WebDAV) variant to route on. We accept the same `calendar-query` body over
+//! `POST` to the collection URL instead; a client or reverse proxy that insists on the literal
+//! `REPORT` verb would need to rewrite it to `POST` in front of this service.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+/// The `<C:time-range>` bounds requested by a `calendar-query` REPORT.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+fn local_name(qualified: &[u8]) -> &str {
+    let s = std::str::from_utf8(qualified).unwrap_or("");
+    s.rsplit(':').next().unwrap_or(s)
+}
+
+/// Parse the `<C:time-range start="..." end="..."/>` out of a `calendar-query` REPORT body.
+///
+/// Only the time-range bound is extracted; the rest of the `<C:filter>`/`<C:comp-filter>` tree
+/// is ignored, since this proxy only ever has one flavor of data (events, or free/busy) to offer
+/// regardless of which component the client filtered on.
+pub fn parse_calendar_query(body: &str) -> anyhow::Result<Option<TimeRange>> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    loop {
+        match reader
+            .read_event_into(&mut buf)
+            .context("Parsing calendar-query XML")?
+        {
+            Event::Empty(e) | Event::Start(e) if local_name(e.name().as_ref()) == "time-range" => {
+                let mut start = None;
+                let mut end = None;
+                for attr in e.attributes().flatten() {
+                    let value = String::from_utf8_lossy(&attr.value).into_owned();
+                    match local_name(attr.key.as_ref()) {
+                        "start" => start = Some(crate::freebusy::parse_ics_time(&value)?),
+                        "end" => end = Some(crate::freebusy::parse_ics_time(&value)?),
+                        _ => (),
+                    }
+                }
+                if let (Some(start), Some(end)) = (start, end) {
+                    return Ok(Some(TimeRange { start, end }));
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(None)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Render a minimal WebDAV `multistatus` response wrapping a single `<C:calendar-data>` response.
+pub fn render_multistatus(href: &str, calendar_data: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <D:multistatus xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\">\n\
+         \x20 <D:response>\n\
+         \x20   <D:href>{href}</D:href>\n\
+         \x20   <D:propstat>\n\
+         \x20     <D:prop>\n\
+         \x20       <C:calendar-data>{data}</C:calendar-data>\n\
+         \x20     </D:prop>\n\
+         \x20     <D:status>HTTP/1.1 200 OK</D:status>\n\
+         \x20   </D:propstat>\n\
+         \x20 </D:response>\n\
+         </D:multistatus>\n",
+        href = xml_escape(href),
+        data = xml_escape(calendar_data),
+    )
+}
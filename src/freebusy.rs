@@ -0,0 +1,139 @@
+//! Collapsing events into aggregate busy/free blocks, as emitted by a `VFREEBUSY` component.
+//!
+//! This reveals strictly less than the per-event `VEVENT` output: a consumer only learns which
+//! time ranges are busy, not how many events make them up, their `UID`s, or their recurrence
+//! structure.
+
+use chrono::{DateTime, TimeZone, Utc};
+use ical::parser::ical::component::{IcalEvent, IcalTimeZone};
+
+use crate::recurrence;
+
+/// A half-open busy interval `[start, end)`.
+pub type Interval = (DateTime<Utc>, DateTime<Utc>);
+
+/// Parse an iCalendar `DATE-TIME` or `DATE` value into a UTC instant, assuming it is either
+/// already UTC (a trailing `Z`) or floating (no zone at all). Used for values that are always UTC
+/// per spec (the `RRULE` `UNTIL` part, CalDAV `<C:time-range>` attributes) — for a `DTSTART`-like
+/// property that may carry a `TZID`, use [`parse_ics_time_with_tz`] instead.
+pub fn parse_ics_time(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    let value = value.trim_end_matches('Z');
+    if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Ok(DateTime::from_naive_utc_and_offset(dt, Utc));
+    }
+    if let Ok(d) = chrono::NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Ok(DateTime::from_naive_utc_and_offset(d.and_hms_opt(0, 0, 0).unwrap(), Utc));
+    }
+    anyhow::bail!("Unsupported DATE-TIME value {:?}", value)
+}
+
+/// Parse an iCalendar `DATE-TIME`/`DATE` value that may be local to `tzid`, resolving it against
+/// the calendar's own `VTIMEZONE`s (`timezones`) on a best-effort basis; see [`crate::timezone`].
+///
+/// Falls back to [`parse_ics_time`]'s naive-UTC behavior when the value already carries a
+/// trailing `Z`, `tzid` is `None` (a floating time), or the named timezone can't be resolved.
+pub fn parse_ics_time_with_tz(
+    value: &str,
+    tzid: Option<&str>,
+    timezones: &[IcalTimeZone],
+) -> anyhow::Result<DateTime<Utc>> {
+    let Some(tzid) = tzid else {
+        return parse_ics_time(value);
+    };
+    if value.ends_with('Z') {
+        return parse_ics_time(value);
+    }
+    let naive = if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        dt
+    } else if let Ok(d) = chrono::NaiveDate::parse_from_str(value, "%Y%m%d") {
+        d.and_hms_opt(0, 0, 0).unwrap()
+    } else {
+        anyhow::bail!("Unsupported DATE-TIME value {:?}", value)
+    };
+    match crate::timezone::resolve_offset(timezones, tzid, naive) {
+        Some(offset) => Ok(offset
+            .from_local_datetime(&naive)
+            .single()
+            .unwrap_or_else(|| DateTime::from_naive_utc_and_offset(naive, Utc))
+            .with_timezone(&Utc)),
+        None => {
+            tracing::warn!(
+                "Could not resolve TZID {:?} against the calendar's VTIMEZONEs, treating it as UTC",
+                tzid
+            );
+            Ok(DateTime::from_naive_utc_and_offset(naive, Utc))
+        }
+    }
+}
+
+fn is_busy(e: &IcalEvent) -> bool {
+    for p in &e.properties {
+        match &p.name as &str {
+            "STATUS" if p.value.as_deref() == Some("CANCELLED") => return false,
+            "TRANSP" if p.value.as_deref() == Some("TRANSPARENT") => return false,
+            _ => (),
+        }
+    }
+    true
+}
+
+/// Collect the busy interval(s) of every busy event intersecting `[win_start, win_end)`,
+/// expanding `RRULE`/`RDATE`/`EXDATE` recurrences as needed.
+///
+/// Events with `STATUS:CANCELLED` or `TRANSP:TRANSPARENT` are excluded, as they do not
+/// contribute to busy-ness. An event without both a `DTSTART` and a `DTEND`, or with a malformed
+/// `RRULE`, is skipped with a warning, rather than failing the whole calendar.
+pub fn collect_busy_intervals(
+    evts: &[IcalEvent],
+    timezones: &[IcalTimeZone],
+    win_start: DateTime<Utc>,
+    win_end: DateTime<Utc>,
+) -> Vec<Interval> {
+    evts.iter()
+        .filter(|e| is_busy(e))
+        .flat_map(|e| recurrence::expand_event(e, timezones, win_start, win_end))
+        .collect()
+}
+
+/// Merge overlapping or adjacent intervals into the minimal disjoint set that covers them.
+pub fn merge_intervals(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.sort_by_key(|(start, _)| *start);
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end => {
+                if end > *last_end {
+                    *last_end = end;
+                }
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn format_ics_time(t: &DateTime<Utc>) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Render a single `VFREEBUSY` component covering the given busy intervals.
+///
+/// `uid` is the component's `UID`, REQUIRED by RFC 5545 §3.6.4 exactly like a `VEVENT`/`VTODO`/
+/// `VJOURNAL`'s; since a `VFREEBUSY` aggregates every event in the window rather than coming from
+/// one source component, the caller is expected to derive a stable value of its own (e.g. by
+/// rehashing something calendar-specific) rather than reusing `rehashed_uid`.
+pub fn render_vfreebusy(intervals: &[Interval], uid: &str) -> String {
+    let mut res = "BEGIN:VFREEBUSY\r\n".to_string();
+    res += &ics_tools::build_property("UID", &None, &Some(uid.to_string()));
+    res += &ics_tools::build_property("DTSTAMP", &None, &Some("20200101T000001Z".to_string()));
+    for (start, end) in intervals {
+        let value = format!("{}/{}", format_ics_time(start), format_ics_time(end));
+        res += &ics_tools::build_property(
+            "FREEBUSY",
+            &Some(vec![("FBTYPE".to_string(), vec!["BUSY".to_string()])]),
+            &Some(value),
+        );
+    }
+    res += "END:VFREEBUSY\r\n";
+    res
+}
@@ -27,6 +27,79 @@ pub async fn parse_remote_ics(url: &url::Url) -> anyhow::Result<IcalCalendar> {
     calendar.with_context(|| format!("Failed to parse the calendar for remote URL {}", url))
 }
 
+/// Escape a single `TEXT`-valued element per RFC 5545 §3.3.11: backslashes, commas, semicolons,
+/// and newlines are backslash-escaped.
+///
+/// This must not be run over a whole list-valued property value (see [`escape_value`]): escaping
+/// every comma in e.g. a multi-value `RDATE` would turn its list separators into literal escaped
+/// commas, collapsing several `DATE-TIME`s into one corrupted value.
+pub fn escape_text(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            ';' => res.push_str("\\;"),
+            ',' => res.push_str("\\,"),
+            '\n' => res.push_str("\\n"),
+            '\r' => (),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Properties whose value is itself a comma-separated list of elements (e.g. multiple
+/// `DATE-TIME`s in a single `RDATE`/`EXDATE`), as opposed to a single `TEXT` value where a
+/// literal comma must be backslash-escaped.
+fn is_list_valued(name: &str) -> bool {
+    matches!(name, "RDATE" | "EXDATE")
+}
+
+/// Escape a property's value for serialization, given its name.
+///
+/// `RECUR`-valued properties (`RRULE`/`EXRULE`) are emitted unchanged, as their `;` and `,` are
+/// the rule's own separators. List-valued properties ([`is_list_valued`]) are escaped
+/// element-by-element and rejoined with plain commas, so their list separators survive;
+/// everything else is escaped as a single `TEXT` value via [`escape_text`].
+pub fn escape_value(name: &str, value: &str) -> String {
+    if matches!(name, "RRULE" | "EXRULE") {
+        value.to_string()
+    } else if is_list_valued(name) {
+        value.split(',').map(escape_text).collect::<Vec<_>>().join(",")
+    } else {
+        escape_text(value)
+    }
+}
+
+/// Fold a content line at 75 octets per RFC 5545 §3.1, and terminate it with a CRLF.
+///
+/// Continuation lines are introduced by a CRLF followed by a single space, which itself counts
+/// towards the 75-octet limit of the continuation line. Folding only ever happens at UTF-8
+/// character boundaries, as required by the spec.
+pub fn fold_line(line: &str) -> String {
+    const FIRST_LIMIT: usize = 75;
+    const CONTINUATION_LIMIT: usize = 74; // + the leading space, for 75 octets total
+    let bytes = line.as_bytes();
+    let mut res = String::new();
+    let mut start = 0;
+    let mut limit = FIRST_LIMIT;
+    loop {
+        let mut end = (start + limit).min(bytes.len());
+        while end < bytes.len() && !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        res += &line[start..end];
+        start = end;
+        if start >= bytes.len() {
+            break;
+        }
+        res += "\r\n ";
+        limit = CONTINUATION_LIMIT;
+    }
+    res += "\r\n";
+    res
+}
+
 pub fn build_property(
     name: &str,
     params: &Option<Vec<(String, Vec<String>)>>,
@@ -35,6 +108,8 @@ pub fn build_property(
     let mut res = name.to_string();
     if let Some(params) = params {
         for p in params {
+            // Parameter values are quoted rather than backslash-escaped: the quoted-string form
+            // is always legal and sidesteps having to special-case which characters require it.
             res = res + ";" + &p.0 + "=\"" + &p.1[0];
             for v in &p.1[1..] {
                 res = res + "\",\"" + v;
@@ -42,12 +117,11 @@ pub fn build_property(
             res += "\"";
         }
     }
-    res += ":\"";
+    res += ":";
     if let Some(value) = value {
-        res += value;
+        res += &escape_value(name, value);
     }
-    res += "\"\n";
-    res
+    fold_line(&res)
 }
 
 pub async fn do_the_thing(
@@ -1,9 +1,17 @@
+mod cache;
+mod caldav;
+mod freebusy;
+mod prune;
+mod recurrence;
+mod timezone;
+
 use std::collections::HashMap;
 use std::net::IpAddr;
 
 use anyhow::{ensure, Context};
 use hmac::Mac;
-use ical::parser::ical::component::{IcalCalendar, IcalEvent, IcalTimeZone};
+use ical::parser::ical::component::{IcalCalendar, IcalEvent, IcalJournal, IcalTimeZone, IcalTodo};
+use ics_tools::build_property;
 use rocket::{http::Status, response::status};
 use structopt::StructOpt;
 use tracing::warn;
@@ -14,8 +22,9 @@ struct Opt {
     /// Path to the configuration file.
     ///
     /// The configuration file only contains a `[calendars]` section, where each element is
-    /// formatted as `path = "remote_url"`. Then, `http://localhost:<port>/<path>` will return an
-    /// anonymized version of `remote_url`.
+    /// formatted as `path = { url = "remote_url" }`, optionally with an `output` field set to
+    /// `"events"` (the default) or `"freebusy"`. Then, `http://localhost:<port>/<path>` will
+    /// return an anonymized version of `remote_url`.
     #[structopt(short, long)]
     config_file: std::path::PathBuf,
 
@@ -36,71 +45,68 @@ struct Cfg {
     /// The seed to use for hashing the UIDs of calendar events. Should ideally be unguessable
     seed: String,
 
-    /// Whether to ignore all unknown properties
-    #[serde(default)] // bool::default() is `false`
-    ignore_unknown_properties: bool,
+    /// Which properties to keep, drop, or keep-without-value, per component
+    #[serde(default)]
+    prune: prune::PruneRules,
+
+    /// How long, in seconds, a fetched remote calendar is considered fresh before it is
+    /// re-fetched, unless the calendar advertises its own `REFRESH-INTERVAL`, or the per-calendar
+    /// `cache_ttl_seconds` override applies
+    #[serde(default = "default_cache_ttl_seconds")]
+    default_cache_ttl_seconds: i64,
 }
 
-#[derive(serde::Deserialize)]
-struct Config {
-    config: Cfg,
-    calendars: HashMap<String, url::Url>,
+fn default_cache_ttl_seconds() -> i64 {
+    3600
 }
 
-async fn parse_remote_ics(url: &url::Url) -> anyhow::Result<IcalCalendar> {
-    // Fetch the remote ICS file
-    let response = reqwest::get(url.as_str())
-        .await
-        .with_context(|| format!("Fetching remote URL {}", url))?;
-    ensure!(
-        response.status().is_success(),
-        "Remote URL {} did not reply with a successful code: {:?}",
-        url,
-        response.status()
-    );
-    let text = response
-        .text()
-        .await
-        .with_context(|| format!("Recovering the text part of the remote URL {}", url))?;
+/// How a calendar's events are rendered in the generated ICS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputMode {
+    /// Emit one scrubbed `VEVENT` per source event (the default).
+    Events,
+    /// Emit a single `VFREEBUSY` component, collapsing events into aggregate busy blocks.
+    Freebusy,
+}
 
-    // And parse it
-    let calendars = ical::IcalParser::new(text.as_bytes()).collect::<Vec<_>>();
-    ensure!(calendars.len() == 1, "Remote URL {} had multiple calendars, this is not supported yet, please open an issue if you have a use case for it", url);
-    let calendar = calendars.into_iter().next().unwrap(); // see ensure! juste above
+impl Default for OutputMode {
+    fn default() -> Self {
+        OutputMode::Events
+    }
+}
 
-    calendar.with_context(|| format!("Failed to parse the calendar for remote URL {}", url))
+#[derive(serde::Deserialize)]
+struct CalendarCfg {
+    /// The remote URL to fetch and anonymize
+    url: url::Url,
+
+    /// How to render this calendar's events
+    #[serde(default)]
+    output: OutputMode,
+
+    /// For `output = "freebusy"`, how many days ahead of now to expand recurring events into
+    /// busy blocks
+    #[serde(default = "default_freebusy_window_days")]
+    freebusy_window_days: i64,
+
+    /// Override `default_cache_ttl_seconds` for this calendar specifically
+    #[serde(default)]
+    cache_ttl_seconds: Option<i64>,
 }
 
-fn build_property(
-    name: &str,
-    params: &Option<Vec<(String, Vec<String>)>>,
-    value: &Option<String>,
-) -> String {
-    let mut res = name.to_string();
-    if let Some(params) = params {
-        for p in params {
-            res = res + ";" + &p.0 + "=" + &p.1[0];
-            for v in &p.1[1..] {
-                res = res + "," + v;
-            }
-        }
-    }
-    res += ":";
-    if let Some(value) = value {
-        res += value;
-    }
-    res += "\n";
-    res
+fn default_freebusy_window_days() -> i64 {
+    90
 }
 
-macro_rules! unknown_property {
-    ($type:expr, $cfg:expr, $propname:expr) => {
-        if $cfg.ignore_unknown_properties {
-            tracing::warn!("Found unknown {} property {}, ignoring", $type, $propname);
-        } else {
-            anyhow::bail!("Found unknown {} property {}, please open an issue and consider using `ignore_unknown_properties`", $type, $propname);
-        }
-    }
+#[derive(serde::Deserialize)]
+struct Config {
+    config: Cfg,
+    calendars: HashMap<String, CalendarCfg>,
+}
+
+fn cache_ttl(calendar: &CalendarCfg, cfg: &Cfg) -> chrono::Duration {
+    chrono::Duration::seconds(calendar.cache_ttl_seconds.unwrap_or(cfg.default_cache_ttl_seconds))
 }
 
 fn handle_calendar_properties(
@@ -109,129 +115,169 @@ fn handle_calendar_properties(
     res: &mut String,
 ) -> anyhow::Result<()> {
     tracing::info!("Property list: {:?}", prop);
-    for p in prop {
-        match &p.name as &str {
-            // Proxy all important properties
-            "CALSCALE" => *res += &build_property("CALSCALE", &p.params, &p.value),
-            // Censor all non-required properties
-            "METHOD" => (),
-            "PRODID" => (),
-            "REFRESH-INTERVAL" => (),
-            "VERSION" if p.value.as_ref().map(|v| v as &str) == Some("2.0") => (),
-            _ if p.name.starts_with("X-") => (),
-            // And either warn or bail on unknown properties
-            _ => unknown_property!("calendar", cfg, p.name),
-        }
-    }
+    prune::prune_component(prop, &cfg.prune.vcalendar, res);
     Ok(())
 }
 
 fn handle_timezones(tzs: &[IcalTimeZone], cfg: &Cfg, res: &mut String) -> anyhow::Result<()> {
     for tz in tzs {
-        *res += "BEGIN:VTIMEZONE\n";
-        for p in &tz.properties {
-            match &p.name as &str {
-                // Proxy all important properties
-                "TZID" => {
-                    *res += &build_property(&p.name, &p.params, &p.value);
-                }
-                // And either warn or bail on the other properties
-                _ => unknown_property!("timezone", cfg, p.name),
-            }
-        }
+        *res += "BEGIN:VTIMEZONE\r\n";
+        prune::prune_component(&tz.properties, &cfg.prune.vtimezone, res);
         for transition in &tz.transitions {
             // TODO: ical doesn't expose whether it's BEGIN:DAYLIGHT or BEGIN:STANDARD
             // It probably doesn't matter anyway? I don't think the spec asks for any differential treatment at least
-            *res += "BEGIN:STANDARD\n";
-            for p in &transition.properties {
-                match &p.name as &str {
-                    // Proxy all important properties
-                    "DTSTART" | "RRULE" | "TZNAME" | "TZOFFSETFROM" | "TZOFFSETTO" => {
-                        *res += &build_property(&p.name, &p.params, &p.value);
-                    }
-                    // And either warn or bail on unknown properties
-                    _ => unknown_property!("timezone transition", cfg, p.name),
-                }
-            }
-            *res += "END:STANDARD\n";
+            *res += "BEGIN:STANDARD\r\n";
+            prune::prune_component(&transition.properties, &cfg.prune.timezone_transition, res);
+            *res += "END:STANDARD\r\n";
         }
-        *res += "END:VTIMEZONE\n";
+        *res += "END:VTIMEZONE\r\n";
     }
     Ok(())
 }
 
+/// Hash `source` keyed by `cfg.seed`, so the result carries stable-but-unguessable identity
+/// across refreshes.
+fn hash_with_seed(source: &str, cfg: &Cfg) -> anyhow::Result<String> {
+    let mut hasher = hmac::Hmac::<sha2::Sha256>::new_from_slice(cfg.seed.as_bytes())
+        .context("Initializing hasher with seed")?;
+    hasher.update(source.as_bytes());
+    Ok(hex::encode(hasher.finalize().into_bytes()))
+}
+
+/// Rehash a component's `UID`, if it has one, so it carries stable-but-unguessable identity
+/// across refreshes rather than being merely kept or dropped like every other property.
+fn rehashed_uid(
+    properties: &[ical::property::Property],
+    cfg: &Cfg,
+) -> anyhow::Result<Option<String>> {
+    for p in properties {
+        if p.name == "UID" {
+            if let Some(value) = &p.value {
+                return Ok(Some(hash_with_seed(value, cfg)?));
+            }
+        }
+    }
+    Ok(None)
+}
+
 fn handle_events(evts: &[IcalEvent], cfg: &Cfg, res: &mut String) -> anyhow::Result<()> {
     for e in evts {
-        *res += &format!(
-            "BEGIN:VEVENT\n\
-             SUMMARY:{}\n\
-             DTSTAMP:20200101T000001Z\n",
-            cfg.message
+        *res += "BEGIN:VEVENT\r\n";
+        *res += &build_property("SUMMARY", &None, &Some(cfg.message.clone()));
+        *res += &build_property("DTSTAMP", &None, &Some("20200101T000001Z".to_string()));
+        if let Some(uid) = rehashed_uid(&e.properties, cfg)? {
+            *res += &build_property("UID", &None, &Some(uid));
+        }
+        prune::prune_component(
+            e.properties.iter().filter(|p| p.name != "UID"),
+            &cfg.prune.vevent,
+            res,
         );
-        // Ignore all alarms, as we only care about busy-ness
-        // Go through the interesting properties
-        for p in &e.properties {
-            match &p.name as &str {
-                // Proxy all important properties
-                "DTSTART" | "DTEND" | "EXDATE" | "EXRULE" | "RDATE" | "RRULE" | "SEQUENCE"
-                | "STATUS" => {
-                    *res += &build_property(&p.name, &p.params, &p.value);
-                }
-                "UID" => {
-                    if let Some(value) = &p.value {
-                        let mut hasher =
-                            hmac::Hmac::<sha2::Sha256>::new_from_slice(cfg.seed.as_bytes())
-                                .context("Initializing hasher with seed")?;
-                        hasher.update(value.as_bytes());
-                        let hash = hasher.finalize().into_bytes();
-                        *res += &format!("UID:{}\n", hex::encode(hash));
-                    }
-                }
-                // Censor all non-required properties
-                "CREATED" => (),
-                "DTSTAMP" => (),
-                "DESCRIPTION" => (),
-                "LAST-MODIFIED" => (),
-                "LOCATION" => (),
-                "SUMMARY" => (),
-                "URL" => (),
-                // And either warn or bail on the other properties
-                _ => unknown_property!("event", cfg, p.name),
-            }
+        *res += "END:VEVENT\r\n";
+    }
+    Ok(())
+}
+
+fn handle_todos(todos: &[IcalTodo], cfg: &Cfg, res: &mut String) -> anyhow::Result<()> {
+    for t in todos {
+        *res += "BEGIN:VTODO\r\n";
+        *res += &build_property("SUMMARY", &None, &Some(cfg.message.clone()));
+        *res += &build_property("DTSTAMP", &None, &Some("20200101T000001Z".to_string()));
+        if let Some(uid) = rehashed_uid(&t.properties, cfg)? {
+            *res += &build_property("UID", &None, &Some(uid));
         }
-        *res += "END:VEVENT\n";
+        prune::prune_component(
+            t.properties.iter().filter(|p| p.name != "UID"),
+            &cfg.prune.vtodo,
+            res,
+        );
+        *res += "END:VTODO\r\n";
     }
     Ok(())
 }
 
-fn generate_ics(cal: IcalCalendar, cfg: &Cfg) -> anyhow::Result<String> {
-    let mut res = "BEGIN:VCALENDAR\n\
-                   VERSION:2.0\n\
-                   PRODID:CALDAV-ANON\n"
-        .to_string();
+fn handle_journals(journals: &[IcalJournal], cfg: &Cfg, res: &mut String) -> anyhow::Result<()> {
+    for j in journals {
+        *res += "BEGIN:VJOURNAL\r\n";
+        *res += &build_property("SUMMARY", &None, &Some(cfg.message.clone()));
+        *res += &build_property("DTSTAMP", &None, &Some("20200101T000001Z".to_string()));
+        if let Some(uid) = rehashed_uid(&j.properties, cfg)? {
+            *res += &build_property("UID", &None, &Some(uid));
+        }
+        prune::prune_component(
+            j.properties.iter().filter(|p| p.name != "UID"),
+            &cfg.prune.vjournal,
+            res,
+        );
+        *res += "END:VJOURNAL\r\n";
+    }
+    Ok(())
+}
+
+fn generate_ics(
+    path: &str,
+    cal: IcalCalendar,
+    cfg: &Cfg,
+    output: OutputMode,
+    freebusy_window_days: i64,
+    time_range: Option<caldav::TimeRange>,
+) -> anyhow::Result<String> {
+    let mut res = "BEGIN:VCALENDAR\r\n".to_string();
+    res += &build_property("VERSION", &None, &Some("2.0".to_string()));
+    res += &build_property("PRODID", &None, &Some("CALDAV-ANON".to_string()));
 
     handle_calendar_properties(&cal.properties, cfg, &mut res)
         .context("Handling the calendar properties")?;
     handle_timezones(&cal.timezones, cfg, &mut res).context("Handling the calendar timezones")?;
-    handle_events(&cal.events, cfg, &mut res).context("Handling the calendar events")?;
+    match output {
+        OutputMode::Events => {
+            let events: Vec<_> = match time_range {
+                Some(range) => cal
+                    .events
+                    .iter()
+                    .filter(|e| {
+                        recurrence::event_occurs_in_window(e, &cal.timezones, range.start, range.end)
+                    })
+                    .cloned()
+                    .collect(),
+                None => cal.events,
+            };
+            handle_events(&events, cfg, &mut res).context("Handling the calendar events")?;
+        }
+        OutputMode::Freebusy => {
+            let (win_start, win_end) = match time_range {
+                Some(range) => (range.start, range.end),
+                None => {
+                    let win_start = chrono::Utc::now();
+                    (win_start, win_start + chrono::Duration::days(freebusy_window_days))
+                }
+            };
+            let intervals = freebusy::merge_intervals(freebusy::collect_busy_intervals(
+                &cal.events,
+                &cal.timezones,
+                win_start,
+                win_end,
+            ));
+            // There's no single source component to rehash a UID from here (VFREEBUSY aggregates
+            // every event in the window), so hash the calendar's own path instead: stable across
+            // refreshes, and distinct per configured calendar, like every other rehashed UID in
+            // this file.
+            let uid = hash_with_seed(&format!("VFREEBUSY:{}", path), cfg)?;
+            res += &freebusy::render_vfreebusy(&intervals, &uid);
+        }
+    }
+    handle_todos(&cal.todos, cfg, &mut res).context("Handling the calendar todos")?;
+    handle_journals(&cal.journals, cfg, &mut res).context("Handling the calendar journals")?;
     ensure!(
         cal.alarms.is_empty(),
         "Parsed calendar had alarms, this is not implemented yet, please open an issue"
     );
-    ensure!(
-        cal.todos.is_empty(),
-        "Parsed calendar had todos, this is not implemented yet, please open an issue"
-    );
-    ensure!(
-        cal.journals.is_empty(),
-        "Parsed calendar had journals, this is not implemented yet, please open an issue"
-    );
     ensure!(
         cal.free_busys.is_empty(),
         "Parsed calendar had free_busys, this is not implemented yet, please open an issue"
     );
 
-    res += "END:VCALENDAR\n";
+    res += "END:VCALENDAR\r\n";
 
     Ok(res)
 }
@@ -240,33 +286,116 @@ fn generate_ics(cal: IcalCalendar, cfg: &Cfg) -> anyhow::Result<String> {
 async fn do_the_thing(
     path: &str,
     cfg: &rocket::State<Config>,
+    cache: &rocket::State<cache::Cache>,
 ) -> Result<String, status::Custom<String>> {
-    let remote_url = cfg.calendars.get(path).ok_or_else(|| {
+    let calendar = cfg.calendars.get(path).ok_or_else(|| {
         status::Custom(
             Status::NotFound,
             format!("Path {} is not configured\n", path),
         )
     })?;
 
-    let remote_ics = parse_remote_ics(&remote_url).await.map_err(|e| {
-        warn!("Error parsing remote ICS: {:?}", e);
+    let remote_ics = cache
+        .fetch(&calendar.url, cache_ttl(calendar, &cfg.config))
+        .await
+        .map_err(|e| {
+            warn!("Error parsing remote ICS: {:?}", e);
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error parsing remote ICS, see the logs for details\n"),
+            )
+        })?;
+    tracing::info!("Got remote ICS {:?}", remote_ics);
+
+    let generated_ics = generate_ics(
+        path,
+        remote_ics,
+        &cfg.config,
+        calendar.output,
+        calendar.freebusy_window_days,
+        None,
+    )
+    .map_err(|e| {
+        warn!("Error generating scrubbed-out ICS from remote ICS: {:?}", e);
         status::Custom(
             Status::InternalServerError,
-            format!("Error parsing remote ICS, see the logs for details\n"),
+            format!("Error generating local ICS, see the logs for details\n"),
+        )
+    })?;
+    tracing::info!("Generated local ICS {:?}", generated_ics);
+
+    Ok(generated_ics)
+}
+
+/// Handle a CalDAV `calendar-query` REPORT, returning only the events (or free/busy) overlapping
+/// the requested `<C:time-range>`.
+///
+/// See the [`caldav`] module docs for why this is mounted on `POST` rather than the literal
+/// `REPORT` method.
+#[rocket::post("/<path>", data = "<body>")]
+async fn calendar_query(
+    path: &str,
+    body: &str,
+    cfg: &rocket::State<Config>,
+    cache: &rocket::State<cache::Cache>,
+) -> Result<status::Custom<String>, status::Custom<String>> {
+    let calendar = cfg.calendars.get(path).ok_or_else(|| {
+        status::Custom(
+            Status::NotFound,
+            format!("Path {} is not configured\n", path),
         )
     })?;
-    tracing::info!("Got remote ICS {:?}", remote_ics);
 
-    let generated_ics = generate_ics(remote_ics, &cfg.config).map_err(|e| {
+    let time_range = caldav::parse_calendar_query(body).map_err(|e| {
+        warn!("Error parsing calendar-query REPORT body: {:?}", e);
+        status::Custom(
+            Status::BadRequest,
+            format!("Malformed calendar-query REPORT body, see the logs for details\n"),
+        )
+    })?;
+
+    let remote_ics = cache
+        .fetch(&calendar.url, cache_ttl(calendar, &cfg.config))
+        .await
+        .map_err(|e| {
+            warn!("Error parsing remote ICS: {:?}", e);
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error parsing remote ICS, see the logs for details\n"),
+            )
+        })?;
+
+    let generated_ics = generate_ics(
+        path,
+        remote_ics,
+        &cfg.config,
+        calendar.output,
+        calendar.freebusy_window_days,
+        time_range,
+    )
+    .map_err(|e| {
         warn!("Error generating scrubbed-out ICS from remote ICS: {:?}", e);
         status::Custom(
             Status::InternalServerError,
             format!("Error generating local ICS, see the logs for details\n"),
         )
     })?;
-    tracing::info!("Generated local ICS {:?}", generated_ics);
 
-    Ok(generated_ics)
+    Ok(status::Custom(
+        Status::MultiStatus,
+        caldav::render_multistatus(&format!("/{}", path), &generated_ics),
+    ))
+}
+
+/// Advertise CalDAV support, as required by clients probing the collection before issuing a
+/// `calendar-query`.
+#[rocket::options("/<_path>")]
+fn calendar_options(_path: &str) -> rocket::response::Response<'static> {
+    rocket::response::Response::build()
+        .status(Status::Ok)
+        .raw_header("DAV", "1, 3, calendar-access")
+        .raw_header("Allow", "OPTIONS, GET, POST")
+        .finalize()
 }
 
 #[rocket::main]
@@ -288,7 +417,11 @@ async fn main() -> anyhow::Result<()> {
     };
     rocket::custom(&rocket_config)
         .manage(config)
-        .mount("/", rocket::routes![do_the_thing])
+        .manage(cache::Cache::default())
+        .mount(
+            "/",
+            rocket::routes![do_the_thing, calendar_query, calendar_options],
+        )
         .launch()
         .await
         .context("Running rocket")
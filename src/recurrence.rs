@@ -0,0 +1,490 @@
+//! Expansion of `RRULE`/`RDATE`/`EXDATE` recurrence sets into concrete occurrence intervals.
+//!
+//! This is deliberately bounded to a query window: a bare `RRULE` without `COUNT`/`UNTIL`
+//! recurs forever, so every caller must supply a `[win_start, win_end)` window past which
+//! expansion stops.
+//!
+//! # Limitations
+//!
+//! - `TZID`s are only resolved against the calendar's `VTIMEZONE`s on a best-effort basis; see
+//!   [`crate::timezone`] and [`crate::freebusy::parse_ics_time_with_tz`].
+//! - `BYDAY` does not support the leading ordinal used for `MONTHLY`/`YEARLY` rules (e.g. the
+//!   `1` in `1MO` for "first Monday"); only the weekday itself is taken into account. This is
+//!   enough for the common `WEEKLY` case, but will over-generate occurrences for ordinal
+//!   `MONTHLY` rules. `YEARLY` rules only test `DTSTART`'s day of the month (once a year, or in
+//!   each `BYMONTH` month) against `BYDAY`, so a `YEARLY` rule with `BYDAY` yields only those of
+//!   these dates that fall on a listed weekday. TODO: support ordinals if this turns out to
+//!   matter in practice.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use ical::parser::ical::component::{IcalEvent, IcalTimeZone};
+
+use crate::freebusy::{parse_ics_time, parse_ics_time_with_tz};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A parsed `RRULE` value.
+#[derive(Debug, Clone, Default)]
+pub struct RecurrenceRule {
+    freq: Option<Freq>,
+    interval: i64,
+    count: Option<u32>,
+    until: Option<DateTime<Utc>>,
+    by_day: HashSet<Weekday>,
+    by_month_day: HashSet<i32>,
+    by_month: HashSet<u32>,
+}
+
+impl RecurrenceRule {
+    /// The rule's `BYMONTH` entries, exposed for [`crate::timezone`]'s best-effort reading of a
+    /// `VTIMEZONE` transition's recurrence.
+    pub(crate) fn by_month(&self) -> &HashSet<u32> {
+        &self.by_month
+    }
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    // Strip a leading ordinal, e.g. the "1" in "1MO" or "-1" in "-1FR".
+    let s = s.trim_start_matches(|c: char| c == '+' || c == '-' || c.is_ascii_digit());
+    match s {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Parse an `RRULE` property value (the part after `RRULE:`) into a [`RecurrenceRule`].
+pub fn parse_rrule(value: &str) -> anyhow::Result<RecurrenceRule> {
+    let mut rule = RecurrenceRule {
+        interval: 1,
+        ..RecurrenceRule::default()
+    };
+    for part in value.split(';') {
+        let (key, val) = part
+            .split_once('=')
+            .with_context(|| format!("Malformed RRULE part {:?}", part))?;
+        match key {
+            "FREQ" => {
+                rule.freq = Some(match val {
+                    "DAILY" => Freq::Daily,
+                    "WEEKLY" => Freq::Weekly,
+                    "MONTHLY" => Freq::Monthly,
+                    "YEARLY" => Freq::Yearly,
+                    other => anyhow::bail!("Unsupported RRULE FREQ {:?}", other),
+                });
+            }
+            "INTERVAL" => {
+                rule.interval = val.parse().context("Parsing RRULE INTERVAL")?;
+            }
+            "COUNT" => {
+                rule.count = Some(val.parse().context("Parsing RRULE COUNT")?);
+            }
+            "UNTIL" => {
+                rule.until = Some(parse_ics_time(val).context("Parsing RRULE UNTIL")?);
+            }
+            "BYDAY" => {
+                for day in val.split(',') {
+                    if let Some(wd) = parse_weekday(day) {
+                        rule.by_day.insert(wd);
+                    }
+                }
+            }
+            "BYMONTHDAY" => {
+                for day in val.split(',') {
+                    rule.by_month_day
+                        .insert(day.parse().context("Parsing RRULE BYMONTHDAY")?);
+                }
+            }
+            "BYMONTH" => {
+                for month in val.split(',') {
+                    rule.by_month
+                        .insert(month.parse().context("Parsing RRULE BYMONTH")?);
+                }
+            }
+            // Other BY* parts and WKST are not supported yet; ignore them rather than bail, as
+            // they only narrow the result set further and so can't cause over-exposure.
+            _ => (),
+        }
+    }
+    anyhow::ensure!(rule.freq.is_some(), "RRULE without a FREQ part");
+    Ok(rule)
+}
+
+/// The number of days in `year`-`month`, used to resolve negative `BYMONTHDAY` values relative to
+/// the end of the month.
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .expect("valid first-of-month date")
+        .pred_opt()
+        .expect("the day before the 1st always exists")
+        .day()
+}
+
+/// Whether `date` matches one of `rule`'s `BYMONTHDAY` entries, resolving negative values (e.g.
+/// `-1` for "last day of the month") relative to `date`'s own month.
+fn matches_month_day(rule: &RecurrenceRule, date: NaiveDate) -> bool {
+    let days_in_month = days_in_month(date.year(), date.month()) as i32;
+    rule.by_month_day.iter().any(|&n| {
+        let day = if n > 0 { n } else { days_in_month + n + 1 };
+        day == date.day() as i32
+    })
+}
+
+fn matches_by_filters(rule: &RecurrenceRule, date: NaiveDate) -> bool {
+    if !rule.by_day.is_empty() && !rule.by_day.contains(&date.weekday()) {
+        return false;
+    }
+    if !rule.by_month_day.is_empty() && !matches_month_day(rule, date) {
+        return false;
+    }
+    if !rule.by_month.is_empty() && !rule.by_month.contains(&date.month()) {
+        return false;
+    }
+    true
+}
+
+/// Candidate start dates for one step of the base frequency, before `BY*` filtering.
+///
+/// When a `BY*` part narrows the frequency's own unit (`BYDAY`/`BYMONTHDAY` for `MONTHLY`,
+/// `BYDAY` for `WEEKLY`, `BYMONTH` for `YEARLY`), every day in that unit is returned (for
+/// `YEARLY` without `BYMONTHDAY`: `DTSTART`'s day of every month) so
+/// [`matches_by_filters`] can select the ones that actually match, instead of anchoring on
+/// `dtstart`'s own day/month and only ever testing that single candidate (which degrades e.g.
+/// `FREQ=WEEKLY;BYDAY=MO,WE,FR` to DTSTART's weekday alone, or `FREQ=YEARLY;BYMONTH=3,6,9,12` to
+/// DTSTART's own month, which almost never matches).
+fn step_candidates(rule: &RecurrenceRule, dtstart: NaiveDate, step: i64) -> Vec<NaiveDate> {
+    match rule.freq.expect("validated by parse_rrule") {
+        Freq::Daily => vec![dtstart + Duration::days(step)],
+        Freq::Weekly => {
+            if rule.by_day.is_empty() {
+                vec![dtstart + Duration::weeks(step)]
+            } else {
+                let week_start = {
+                    let base = dtstart + Duration::weeks(step);
+                    base - Duration::days(base.weekday().num_days_from_monday() as i64)
+                };
+                (0..7).map(|d| week_start + Duration::days(d)).collect()
+            }
+        }
+        Freq::Monthly => {
+            let total_months = dtstart.month0() as i64 + step;
+            let year = dtstart.year() + (total_months.div_euclid(12)) as i32;
+            let month = total_months.rem_euclid(12) as u32 + 1;
+            if rule.by_month_day.is_empty() && rule.by_day.is_empty() {
+                NaiveDate::from_ymd_opt(year, month, dtstart.day())
+                    .into_iter()
+                    .collect()
+            } else {
+                (1..=days_in_month(year, month))
+                    .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+                    .collect()
+            }
+        }
+        Freq::Yearly => {
+            let year = dtstart.year() + step as i32;
+            if rule.by_month.is_empty() {
+                NaiveDate::from_ymd_opt(year, dtstart.month(), dtstart.day())
+                    .into_iter()
+                    .collect()
+            } else if rule.by_month_day.is_empty() {
+                (1..=12u32)
+                    .filter_map(|month| NaiveDate::from_ymd_opt(year, month, dtstart.day()))
+                    .collect()
+            } else {
+                (1..=12u32)
+                    .flat_map(|month| {
+                        (1..=days_in_month(year, month))
+                            .filter_map(move |d| NaiveDate::from_ymd_opt(year, month, d))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Expand a recurring event's `RRULE`/`RDATE`/`EXDATE` set into the concrete occurrence
+/// intervals intersecting `[win_start, win_end)`.
+///
+/// `duration` is carried over from the original event (`DTEND - DTSTART`) to every instance.
+/// Expansion is capped at `win_end` even when the rule has neither `COUNT` nor `UNTIL`.
+pub fn expand(
+    dtstart: DateTime<Utc>,
+    duration: Duration,
+    rrule: Option<&RecurrenceRule>,
+    rdate: &[DateTime<Utc>],
+    exdate: &[DateTime<Utc>],
+    win_start: DateTime<Utc>,
+    win_end: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut starts: Vec<DateTime<Utc>> = Vec::new();
+
+    if let Some(rule) = rrule {
+        let dtstart_date = dtstart.date_naive();
+        let mut occurrence_count = 0u32;
+        let mut step = 0i64;
+        // Bound the number of steps we ever take so a rule without COUNT/UNTIL can't loop
+        // forever even when win_end is far away: one step per day in the window plus a year of
+        // slack is always enough headroom for DAILY, the densest supported frequency.
+        let max_steps = (win_end - dtstart).num_days().max(0) + 366;
+        'steps: while step <= max_steps {
+            for candidate in step_candidates(rule, dtstart_date, step * rule.interval) {
+                if !matches_by_filters(rule, candidate) {
+                    continue;
+                }
+                let start = candidate.and_time(dtstart.time()).and_utc();
+                if let Some(until) = rule.until {
+                    if start > until {
+                        break 'steps;
+                    }
+                }
+                occurrence_count += 1;
+                if let Some(count) = rule.count {
+                    if occurrence_count > count {
+                        break 'steps;
+                    }
+                }
+                if start >= win_end {
+                    break 'steps;
+                }
+                starts.push(start);
+            }
+            step += 1;
+        }
+    } else {
+        starts.push(dtstart);
+    }
+
+    starts.extend(rdate.iter().copied());
+
+    let exdate: HashSet<DateTime<Utc>> = exdate.iter().copied().collect();
+    starts
+        .into_iter()
+        .filter(|s| !exdate.contains(s))
+        .filter(|s| *s < win_end && *s + duration > win_start)
+        .map(|s| (s, s + duration))
+        .collect()
+}
+
+/// The `TZID` parameter of a `DTSTART`/`DTEND`/`RDATE`/`EXDATE` property, if it has one.
+fn tzid_param(p: &ical::property::Property) -> Option<&str> {
+    p.params
+        .as_ref()?
+        .iter()
+        .find(|(name, _)| name == "TZID")
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// Expand a single `VEVENT`'s occurrence intervals intersecting `[win_start, win_end)`.
+///
+/// Reads `DTSTART`/`DTEND`/`RRULE`/`RDATE`/`EXDATE` off the event and delegates to [`expand`].
+/// `timezones` is the calendar's own `VTIMEZONE` list, used to resolve any `TZID` parameter on
+/// those properties (see [`crate::timezone`]). An event without both a `DTSTART` and a `DTEND`,
+/// or with a malformed `RRULE`, yields no intervals (with a warning), rather than failing the
+/// whole calendar.
+pub fn expand_event(
+    e: &IcalEvent,
+    timezones: &[IcalTimeZone],
+    win_start: DateTime<Utc>,
+    win_end: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut dtstart = None;
+    let mut dtend = None;
+    let mut rrule = None;
+    let mut rdate = Vec::new();
+    let mut exdate = Vec::new();
+    for p in &e.properties {
+        match &p.name as &str {
+            "DTSTART" => dtstart = Some((p.value.as_deref(), tzid_param(p))),
+            "DTEND" => dtend = Some((p.value.as_deref(), tzid_param(p))),
+            "RRULE" => rrule = p.value.as_deref(),
+            "RDATE" => rdate.extend(
+                p.value
+                    .iter()
+                    .flat_map(|v| v.split(','))
+                    .map(|v| (v, tzid_param(p))),
+            ),
+            "EXDATE" => exdate.extend(
+                p.value
+                    .iter()
+                    .flat_map(|v| v.split(','))
+                    .map(|v| (v, tzid_param(p))),
+            ),
+            _ => (),
+        }
+    }
+    let (start, end) = match (dtstart, dtend) {
+        (Some((Some(start), start_tzid)), Some((Some(end), end_tzid))) => match (
+            parse_ics_time_with_tz(start, start_tzid, timezones),
+            parse_ics_time_with_tz(end, end_tzid, timezones),
+        ) {
+            (Ok(start), Ok(end)) => (start, end),
+            (start, end) => {
+                tracing::warn!(
+                    "Failed to parse DTSTART/DTEND of an event, skipping it: {:?} / {:?}",
+                    start,
+                    end
+                );
+                return Vec::new();
+            }
+        },
+        _ => {
+            tracing::warn!("Event without both DTSTART and DTEND, skipping it");
+            return Vec::new();
+        }
+    };
+    let duration = end - start;
+    let rrule = match rrule.map(parse_rrule).transpose() {
+        Ok(rrule) => rrule,
+        Err(err) => {
+            tracing::warn!("Failed to parse RRULE, skipping event: {:?}", err);
+            return Vec::new();
+        }
+    };
+    let rdate: Vec<_> = rdate
+        .iter()
+        .filter_map(|(d, tzid)| parse_ics_time_with_tz(d, *tzid, timezones).ok())
+        .collect();
+    let exdate: Vec<_> = exdate
+        .iter()
+        .filter_map(|(d, tzid)| parse_ics_time_with_tz(d, *tzid, timezones).ok())
+        .collect();
+    expand(start, duration, rrule.as_ref(), &rdate, &exdate, win_start, win_end)
+}
+
+/// Whether an event has any occurrence intersecting `[win_start, win_end)`.
+pub fn event_occurs_in_window(
+    e: &IcalEvent,
+    timezones: &[IcalTimeZone],
+    win_start: DateTime<Utc>,
+    win_end: DateTime<Utc>,
+) -> bool {
+    !expand_event(e, timezones, win_start, win_end).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn dt(y: i32, m: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, mi, s).unwrap()
+    }
+
+    #[test]
+    fn parse_rrule_weekly_byday() {
+        let rule = parse_rrule("FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=6").unwrap();
+        assert_eq!(rule.freq, Some(Freq::Weekly));
+        assert_eq!(rule.count, Some(6));
+        assert_eq!(rule.interval, 1);
+        assert!(rule.by_day.contains(&Weekday::Mon));
+        assert!(rule.by_day.contains(&Weekday::Wed));
+        assert!(rule.by_day.contains(&Weekday::Fri));
+        assert!(!rule.by_day.contains(&Weekday::Tue));
+    }
+
+    #[test]
+    fn expand_weekly_byday_covers_every_listed_weekday() {
+        // 2024-01-01 is a Monday.
+        let dtstart = dt(2024, 1, 1, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=WEEKLY;BYDAY=MO,WE,FR").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2024, 1, 15, 0, 0, 0); // two full weeks
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        assert_eq!(occurrences.len(), 6);
+        assert!(occurrences
+            .iter()
+            .all(|(s, _)| matches!(s.weekday(), Weekday::Mon | Weekday::Wed | Weekday::Fri)));
+    }
+
+    #[test]
+    fn expand_respects_count() {
+        let dtstart = dt(2024, 1, 1, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=DAILY;COUNT=3").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2025, 1, 1, 0, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        assert_eq!(occurrences.len(), 3);
+    }
+
+    #[test]
+    fn expand_respects_until() {
+        let dtstart = dt(2024, 1, 1, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=DAILY;UNTIL=20240103T090000Z").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2024, 2, 1, 0, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        // Jan 1, 2, 3 — UNTIL is inclusive of the exact instant.
+        assert_eq!(occurrences.len(), 3);
+    }
+
+    #[test]
+    fn expand_excludes_occurrences_outside_window() {
+        let dtstart = dt(2024, 1, 1, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=DAILY;COUNT=10").unwrap();
+        // The window covers only the instant the first occurrence starts, up to (but excluding)
+        // the instant the second one starts.
+        let win_start = dt(2024, 1, 1, 9, 0, 0);
+        let win_end = dt(2024, 1, 2, 9, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        assert_eq!(occurrences, vec![(dtstart, dtstart + duration)]);
+    }
+
+    #[test]
+    fn expand_yearly_bymonth_enumerates_every_listed_month() {
+        let dtstart = dt(2024, 1, 15, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=YEARLY;BYMONTH=3,6,9,12;COUNT=4").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2025, 1, 1, 0, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        let months: Vec<_> = occurrences.iter().map(|(s, _)| s.month()).collect();
+        assert_eq!(months, vec![3, 6, 9, 12]);
+        assert!(occurrences.iter().all(|(s, _)| s.day() == 15));
+    }
+
+    #[test]
+    fn expand_monthly_byday_without_bymonthday_covers_every_matching_weekday() {
+        // 2024-01-01 is a Monday; January 2024 has Mondays on 1, 8, 15, 22, 29.
+        let dtstart = dt(2024, 1, 1, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=MONTHLY;BYDAY=MO;COUNT=4").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2024, 2, 1, 0, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        let days: Vec<_> = occurrences.iter().map(|(s, _)| s.day()).collect();
+        assert_eq!(days, vec![1, 8, 15, 22]);
+    }
+
+    #[test]
+    fn expand_negative_bymonthday_resolves_relative_to_month_end() {
+        let dtstart = dt(2024, 1, 31, 9, 0, 0);
+        let duration = Duration::hours(1);
+        let rule = parse_rrule("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3").unwrap();
+        let win_start = dt(2024, 1, 1, 0, 0, 0);
+        let win_end = dt(2024, 5, 1, 0, 0, 0);
+        let occurrences = expand(dtstart, duration, Some(&rule), &[], &[], win_start, win_end);
+        let days: Vec<_> = occurrences.iter().map(|(s, _)| s.day()).collect();
+        // Jan 31, Feb 29 (2024 is a leap year), Mar 31.
+        assert_eq!(days, vec![31, 29, 31]);
+    }
+}
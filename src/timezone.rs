@@ -0,0 +1,81 @@
+//! Best-effort resolution of `TZID`-qualified `DATE-TIME` values against the calendar's own
+//! `VTIMEZONE` definitions, used by [`crate::freebusy::parse_ics_time_with_tz`].
+//!
+//! # Limitations
+//!
+//! A `STANDARD`/`DAYLIGHT` transition's recurrence is only read for its `BYMONTH`; the ordinal
+//! `BYDAY` (e.g. the `1` in `1SU` for "first Sunday") that pins down the exact transition day is
+//! not evaluated, matching the same limitation documented in [`crate::recurrence`]. Whichever
+//! transition's month is the most recent one at or before the queried date is used, which is
+//! correct away from the transition boundary itself but may be off by up to a few weeks right
+//! around a DST change.
+
+use chrono::{Datelike, FixedOffset, NaiveDateTime};
+use ical::parser::ical::component::IcalTimeZone;
+
+use crate::recurrence;
+
+/// Parse a `TZOFFSETTO`/`TZOFFSETFROM`-style offset, e.g. `-0500` or `+0100`.
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let hours: i32 = value.get(0..2)?.parse().ok()?;
+    let minutes: i32 = value.get(2..4)?.parse().ok()?;
+    let seconds: i32 = value.get(4..6).and_then(|s| s.parse().ok()).unwrap_or(0);
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
+struct Transition {
+    /// The month this transition falls in, per its `RRULE`'s `BYMONTH` (see module limitations).
+    /// `None` when the transition has no recurrence (or none we can read a month out of), in
+    /// which case it always qualifies in [`resolve_offset`], but ranks below any transition that
+    /// has a month.
+    month: Option<u32>,
+    offset: FixedOffset,
+}
+
+fn transitions(tz: &IcalTimeZone) -> Vec<Transition> {
+    tz.transitions
+        .iter()
+        .filter_map(|t| {
+            let offset = t
+                .properties
+                .iter()
+                .find(|p| p.name == "TZOFFSETTO")
+                .and_then(|p| p.value.as_deref())
+                .and_then(parse_offset)?;
+            let month = t
+                .properties
+                .iter()
+                .find(|p| p.name == "RRULE")
+                .and_then(|p| p.value.as_deref())
+                .and_then(|v| recurrence::parse_rrule(v).ok())
+                .and_then(|rule| rule.by_month().iter().min().copied());
+            Some(Transition { month, offset })
+        })
+        .collect()
+}
+
+/// Resolve the UTC offset in effect for `tzid` at the naive local date-time `at`, using the
+/// calendar's own `VTIMEZONE` definitions. Returns `None` if `tzid` doesn't match any of
+/// `timezones`, or the matching `VTIMEZONE` has no usable transition.
+pub fn resolve_offset(timezones: &[IcalTimeZone], tzid: &str, at: NaiveDateTime) -> Option<FixedOffset> {
+    let tz = timezones.iter().find(|tz| {
+        tz.properties
+            .iter()
+            .any(|p| p.name == "TZID" && p.value.as_deref() == Some(tzid))
+    })?;
+    let candidates = transitions(tz);
+    let month = at.month();
+    // The transition in effect is the most recent one whose month is at or before the queried
+    // month; if none qualifies, the latest transition in the previous year (i.e. the one with the
+    // greatest month) is still the one in effect.
+    candidates
+        .iter()
+        .filter(|t| t.month.map_or(true, |m| m <= month))
+        .max_by_key(|t| t.month.unwrap_or(0))
+        .or_else(|| candidates.iter().max_by_key(|t| t.month.unwrap_or(0)))
+        .map(|t| t.offset)
+}